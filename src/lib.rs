@@ -0,0 +1,3 @@
+pub mod networkcfg;
+pub mod nmmgr;
+pub mod profile;
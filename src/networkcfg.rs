@@ -2,40 +2,104 @@ extern crate glib;
 
 use super::nmmgr::MapNMManger;
 use eyre::Result;
-use glib::Cast;
+use glib::{Cast, ToVariant};
 use libc;
 use nm::{
-    Client, ConnectionExt, IPAddress, SettingIP4Config, SettingIP6Config, SettingIPConfig,
+    Client, ConnectionExt, IPAddress, IPRoute, SettingIP4Config, SettingIP6Config, SettingIPConfig,
     SettingIPConfigExt, SETTING_IP4_CONFIG_METHOD_AUTO, SETTING_IP4_CONFIG_METHOD_MANUAL,
     SETTING_IP6_CONFIG_METHOD_AUTO, SETTING_IP6_CONFIG_METHOD_MANUAL,
 };
+use serde::{Deserialize, Serialize};
 use std::{fmt::Display, net::IpAddr};
 
+/// A static route belonging to an `IPConfig`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub dest: IpAddr,
+    pub prefix: u32,
+    pub next_hop: Option<IpAddr>,
+    pub metric: Option<u32>,
+}
+
 /// The Ip configuration struct
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IPConfig {
-    address: IpAddr,
+    addresses: Vec<(IpAddr, u32)>,
     gateway: Option<IpAddr>,
-    dns: Option<IpAddr>,
-    prefix: u32,
+    #[serde(default)]
+    dns: Vec<IpAddr>,
+    #[serde(default)]
+    dns_search: Vec<String>,
+    #[serde(default)]
+    routes: Vec<Route>,
 }
 
-impl Display for IPConfig {
+impl Display for Route {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "address: {}/{}\ngateway: {}\ndns: {}",
-            self.address,
+            "{}/{} via {} metric {}",
+            self.dest,
             self.prefix,
+            self.next_hop.map_or(String::new(), |x| x.to_string()),
+            self.metric.map_or(String::new(), |x| x.to_string())
+        )
+    }
+}
+
+impl Display for IPConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|(address, prefix)| format!("{}/{}", address, prefix))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let dns = self
+            .dns
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let routes = self
+            .routes
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            f,
+            "address: {}\ngateway: {}\ndns: {}\ndns_search: {}\nroutes: {}",
+            addresses,
             self.gateway.map_or(String::new(), |x| x.to_string()),
-            self.dns.map_or(String::new(), |x| x.to_string())
+            dns,
+            self.dns_search.join(", "),
+            routes
         )
     }
 }
 
+/// DHCP client options applied to each protocol of a connection that has
+/// no manual `IPConfig` for it (the config is `None`), letting an
+/// otherwise-AUTO connection still pin a hostname, client id or DNS/route
+/// behaviour. An `IPConfig` with an empty address list still goes through
+/// the manual path.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DhcpConfig {
+    pub hostname: Option<String>,
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub send_hostname: bool,
+    #[serde(default)]
+    pub ignore_auto_dns: bool,
+    #[serde(default)]
+    pub ignore_auto_routes: bool,
+    pub route_metric: Option<i64>,
+}
+
 /// A simple Network Config consists of connection name,
 /// IpV4 configuration and IpV6 configuration.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NetworkConfig {
     /// The connection name
     name: String,
@@ -43,6 +107,8 @@ pub struct NetworkConfig {
     ipv4cfg: Option<IPConfig>,
     /// The IpV6 Config of the connection
     ipv6cfg: Option<IPConfig>,
+    /// DHCP client options applied to either protocol's AUTO path
+    dhcpcfg: Option<DhcpConfig>,
 }
 
 impl Display for NetworkConfig {
@@ -64,30 +130,81 @@ impl Display for NetworkConfig {
 }
 
 impl IPConfig {
-    fn from_settings(settings: SettingIPConfig) -> Option<Self> {
+    /// Read every field off a live `SettingIPConfig`, regardless of its
+    /// method. Shared by both `from_settings` and `from_settings_report`,
+    /// which differ only in which methods they consider meaningful.
+    fn read_settings(settings: &SettingIPConfig) -> Self {
+        let mut addresses = Vec::new();
+        let mut i = 0;
+        while let Some(ipaddr) = settings.address(i) {
+            if let Some(address) = ipaddr.address() {
+                addresses.push((address.parse().unwrap(), ipaddr.prefix()));
+            }
+            i += 1;
+        }
+        let gateway = settings.gateway().map(|x| x.to_string().parse().unwrap());
+        let mut dns = Vec::new();
+        let mut i = 0;
+        while let Some(addr) = settings.dns(i) {
+            dns.push(addr.to_string().parse().unwrap());
+            i += 1;
+        }
+        let mut dns_search = Vec::new();
+        let mut i = 0;
+        while let Some(domain) = settings.dns_search(i) {
+            dns_search.push(domain.to_string());
+            i += 1;
+        }
+        let mut routes = Vec::new();
+        let mut i = 0;
+        while let Some(route) = settings.route(i) {
+            if let Some(dest) = route.dest() {
+                let metric = route
+                    .attribute("metric")
+                    .and_then(|v| v.get::<i64>())
+                    .map(|m| m as u32);
+                routes.push(Route {
+                    dest: dest.parse().unwrap(),
+                    prefix: route.prefix(),
+                    next_hop: route.next_hop().map(|x| x.to_string().parse().unwrap()),
+                    metric,
+                });
+            }
+            i += 1;
+        }
+        IPConfig {
+            addresses,
+            gateway,
+            dns,
+            dns_search,
+            routes,
+        }
+    }
+
+    /// Read a manual `IPConfig` back from a live connection. Returns `None`
+    /// for anything other than MANUAL, including AUTO, since
+    /// `NetworkConfig::new_future` stores the result, a later `save` writes
+    /// it back through `set_manual`, and an AUTO connection must keep going
+    /// through `handle_dhcp`.
+    pub(crate) fn from_settings(settings: SettingIPConfig) -> Option<Self> {
         match settings.method() {
             Some(val) if val == *SETTING_IP4_CONFIG_METHOD_MANUAL => {
-                let addr: IpAddr;
-                let prefix: u32;
-                let gateway: Option<IpAddr>;
-                let dns: Option<IpAddr>;
-                if let Some((Some(address), prefix_)) = settings
-                    .address(0)
-                    .map(|ipaddr| (ipaddr.address(), ipaddr.prefix()))
-                {
-                    addr = address.parse().unwrap();
-                    prefix = prefix_;
-                    gateway = settings.gateway().map(|x| x.to_string().parse().unwrap());
-                    dns = settings.dns(0).map(|x| x.to_string().parse().unwrap());
-                    return Some(IPConfig {
-                        address: addr,
-                        prefix,
-                        gateway,
-                        dns,
-                    });
-                } else {
-                    return None;
-                };
+                Some(Self::read_settings(&settings))
+            }
+            _ => None,
+        }
+    }
+
+    /// Read an `IPConfig` back from a live connection for reporting only
+    /// (`MapNMManger::dump_state`), covering AUTO connections too. The
+    /// result must never be handed to `NetworkConfig::save`.
+    pub(crate) fn from_settings_report(settings: SettingIPConfig) -> Option<Self> {
+        match settings.method() {
+            Some(val)
+                if val == *SETTING_IP4_CONFIG_METHOD_MANUAL
+                    || val == *SETTING_IP4_CONFIG_METHOD_AUTO =>
+            {
+                Some(Self::read_settings(&settings))
             }
             _ => None,
         }
@@ -95,6 +212,24 @@ impl IPConfig {
 }
 
 impl NetworkConfig {
+    /// Build a NetworkConfig from already-known configuration, without
+    /// reading anything back from NetworkManager. Used by callers such as
+    /// `NetworkProfile` that assemble configs from a declarative source and
+    /// only need to `save` them.
+    pub fn new(
+        name: &str,
+        ipv4cfg: Option<IPConfig>,
+        ipv6cfg: Option<IPConfig>,
+        dhcpcfg: Option<DhcpConfig>,
+    ) -> Self {
+        NetworkConfig {
+            name: name.to_string(),
+            ipv4cfg,
+            ipv6cfg,
+            dhcpcfg,
+        }
+    }
+
     pub async fn new_future(name: &str) -> Result<Self> {
         let mut mapnm_manger = MapNMManger::new_future(None).await?;
         let conn = mapnm_manger.connection_by_name(name).await?;
@@ -110,6 +245,7 @@ impl NetworkConfig {
             name: name.to_string(),
             ipv4cfg,
             ipv6cfg,
+            dhcpcfg: None,
         })
     }
 
@@ -124,36 +260,67 @@ impl NetworkConfig {
         Ok(())
     }
 
-    /// Set address, dns and gateway from configuration to NetworkManager
-    /// Connection.
-    fn set_manual(nm_ipcfg: &impl SettingIPConfigExt, ipcfg: &IPConfig) -> Result<()> {
+    /// Set addresses, dns, dns search domains and gateway from configuration
+    /// to NetworkManager Connection.
+    fn set_manual(
+        nm_ipcfg: &impl SettingIPConfigExt,
+        version: u32,
+        ipcfg: &IPConfig,
+    ) -> Result<()> {
         nm_ipcfg.clear_addresses();
         nm_ipcfg.clear_dns();
-        let new_addr: _;
-        let inet: _;
-        match ipcfg.address {
-            IpAddr::V4(_) => {
-                inet = libc::AF_INET;
-                nm_ipcfg.set_method(Some(&SETTING_IP4_CONFIG_METHOD_MANUAL));
-            }
-            IpAddr::V6(_) => {
-                inet = libc::AF_INET6;
-                nm_ipcfg.set_method(Some(&SETTING_IP6_CONFIG_METHOD_MANUAL));
-            }
+        nm_ipcfg.clear_dns_searches();
+        if version == 4 {
+            nm_ipcfg.set_method(Some(&SETTING_IP4_CONFIG_METHOD_MANUAL));
+        } else {
+            nm_ipcfg.set_method(Some(&SETTING_IP6_CONFIG_METHOD_MANUAL));
+        }
+        for (address, prefix) in &ipcfg.addresses {
+            let inet = match address {
+                IpAddr::V4(_) => libc::AF_INET,
+                IpAddr::V6(_) => libc::AF_INET6,
+            };
+            let new_addr = IPAddress::new(inet, &address.to_string(), *prefix)?;
+            nm_ipcfg.add_address(&new_addr);
         }
-        new_addr = IPAddress::new(inet, &ipcfg.address.to_string(), ipcfg.prefix)?;
-        nm_ipcfg.add_address(&new_addr);
         if let Some(gateway) = ipcfg.gateway {
-            nm_ipcfg.add_dns(gateway.to_string().as_str());
+            nm_ipcfg.set_gateway(Some(gateway.to_string().as_str()));
         }
-        if let Some(dns) = ipcfg.dns {
+        for dns in &ipcfg.dns {
             nm_ipcfg.add_dns(dns.to_string().as_str());
         }
+        for search in &ipcfg.dns_search {
+            nm_ipcfg.add_dns_search(search.as_str());
+        }
+        nm_ipcfg.clear_routes();
+        for route in &ipcfg.routes {
+            let inet = match route.dest {
+                IpAddr::V4(_) => libc::AF_INET,
+                IpAddr::V6(_) => libc::AF_INET6,
+            };
+            let next_hop = route.next_hop.map(|x| x.to_string());
+            let nm_route = IPRoute::new(
+                inet,
+                &route.dest.to_string(),
+                route.prefix,
+                next_hop.as_deref(),
+            )?;
+            if let Some(metric) = route.metric {
+                nm_route.set_attribute("metric", &(metric as i64).to_variant());
+            }
+            nm_ipcfg.add_route(&nm_route);
+        }
         Ok(())
     }
 
-    /// Set the NetworkManager Connection to DHCP.
-    fn set_dhcp(nm_ipcfg: &impl SettingIPConfigExt, version: u32) -> Result<()> {
+    /// Set the NetworkManager Connection to DHCP, applying any client
+    /// options from `dhcpcfg` so headless boxes can still pin a hostname
+    /// or prefer static DNS/routes while staying on AUTO.
+    fn set_dhcp(
+        nm_ipcfg: &impl SettingIPConfigExt,
+        version: u32,
+        dhcpcfg: &Option<DhcpConfig>,
+    ) -> Result<()> {
         nm_ipcfg.clear_addresses();
         nm_ipcfg.clear_dns();
         nm_ipcfg.set_gateway(None);
@@ -162,6 +329,20 @@ impl NetworkConfig {
         } else {
             nm_ipcfg.set_method(Some(&SETTING_IP6_CONFIG_METHOD_AUTO));
         }
+        if let Some(dhcp) = dhcpcfg {
+            if let Some(hostname) = &dhcp.hostname {
+                nm_ipcfg.set_dhcp_hostname(Some(hostname));
+            }
+            if let Some(client_id) = &dhcp.client_id {
+                nm_ipcfg.set_dhcp_client_id(Some(client_id));
+            }
+            nm_ipcfg.set_dhcp_send_hostname(dhcp.send_hostname);
+            nm_ipcfg.set_ignore_auto_dns(dhcp.ignore_auto_dns);
+            nm_ipcfg.set_ignore_auto_routes(dhcp.ignore_auto_routes);
+            if let Some(route_metric) = dhcp.route_metric {
+                nm_ipcfg.set_route_metric(route_metric);
+            }
+        }
         Ok(())
     }
 
@@ -175,10 +356,16 @@ impl NetworkConfig {
                 NetworkConfig::handle_manual(
                     &connection.setting_ip4_config(),
                     connection,
+                    4,
                     ipv4cfg,
                 )?;
             }
-            None => NetworkConfig::handle_dhcp(&connection.setting_ip4_config(), connection, 4)?,
+            None => NetworkConfig::handle_dhcp(
+                &connection.setting_ip4_config(),
+                connection,
+                4,
+                &self.dhcpcfg,
+            )?,
         }
         Ok(())
     }
@@ -193,10 +380,16 @@ impl NetworkConfig {
                 NetworkConfig::handle_manual(
                     &connection.setting_ip6_config(),
                     connection,
+                    6,
                     ipv6cfg,
                 )?;
             }
-            None => NetworkConfig::handle_dhcp(&connection.setting_ip6_config(), connection, 6)?,
+            None => NetworkConfig::handle_dhcp(
+                &connection.setting_ip6_config(),
+                connection,
+                6,
+                &self.dhcpcfg,
+            )?,
         }
         Ok(())
     }
@@ -205,23 +398,19 @@ impl NetworkConfig {
     fn handle_manual(
         nm_ipcfg: &Option<impl SettingIPConfigExt>,
         connection: &nm::RemoteConnection,
+        version: u32,
         ipcfg: &IPConfig,
     ) -> Result<()> {
         if let Some(nm_ipcfg_) = nm_ipcfg {
-            NetworkConfig::set_manual(nm_ipcfg_, ipcfg)?;
+            NetworkConfig::set_manual(nm_ipcfg_, version, ipcfg)?;
+        } else if version == 6 {
+            let nm_ipcfg = SettingIP6Config::new();
+            NetworkConfig::set_manual(&nm_ipcfg, version, ipcfg)?;
+            connection.add_setting(&nm_ipcfg);
         } else {
-            match ipcfg.address {
-                IpAddr::V4(_) => {
-                    let nm_ipcfg = SettingIP4Config::new();
-                    NetworkConfig::set_manual(&nm_ipcfg, ipcfg)?;
-                    connection.add_setting(&nm_ipcfg);
-                }
-                IpAddr::V6(_) => {
-                    let nm_ipcfg = SettingIP6Config::new();
-                    NetworkConfig::set_manual(&nm_ipcfg, ipcfg)?;
-                    connection.add_setting(&nm_ipcfg);
-                }
-            }
+            let nm_ipcfg = SettingIP4Config::new();
+            NetworkConfig::set_manual(&nm_ipcfg, version, ipcfg)?;
+            connection.add_setting(&nm_ipcfg);
         }
         Ok(())
     }
@@ -231,17 +420,18 @@ impl NetworkConfig {
         nm_ipcfg: &Option<impl SettingIPConfigExt>,
         connection: &nm::RemoteConnection,
         version: u32,
+        dhcpcfg: &Option<DhcpConfig>,
     ) -> Result<()> {
         if let Some(nm_ipcfg) = nm_ipcfg {
-            NetworkConfig::set_dhcp(nm_ipcfg, version)?;
+            NetworkConfig::set_dhcp(nm_ipcfg, version, dhcpcfg)?;
         } else {
             if version == 4 {
                 let nm_ipcfg = SettingIP4Config::new();
-                NetworkConfig::set_dhcp(&nm_ipcfg, version)?;
+                NetworkConfig::set_dhcp(&nm_ipcfg, version, dhcpcfg)?;
                 connection.add_setting(&nm_ipcfg);
             } else {
                 let nm_ipcfg = SettingIP6Config::new();
-                NetworkConfig::set_dhcp(&nm_ipcfg, version)?;
+                NetworkConfig::set_dhcp(&nm_ipcfg, version, dhcpcfg)?;
                 connection.add_setting(&nm_ipcfg);
             }
         }
@@ -261,12 +451,14 @@ mod tests {
                 let example = NetworkConfig {
                     name: "eth0".to_string(),
                     ipv4cfg: Some(IPConfig {
-                        address: "192.168.233.233".parse().unwrap(),
+                        addresses: vec![("192.168.233.233".parse().unwrap(), 32)],
                         gateway: Some("192.168.233.1".parse().unwrap()),
-                        dns: Some("8.8.8.8".parse().unwrap()),
-                        prefix: 32,
+                        dns: vec!["8.8.8.8".parse().unwrap()],
+                        dns_search: vec![],
+                        routes: vec![],
                     }),
                     ipv6cfg: None,
+                    dhcpcfg: None,
                 };
                 ctx.block_on(example.save()).unwrap();
                 println!(
@@ -1,8 +1,15 @@
-use eyre::Result;
+use super::networkcfg::IPConfig;
+use eyre::{eyre, Result};
+use futures::channel::mpsc;
+use futures::Stream;
+use glib::Cast;
 use nm::{
-    Client, ConnectionExt, DeviceExt, DeviceType, SettingConnection, SimpleConnection,
-    SETTING_WIRED_SETTING_NAME,
+    ActiveConnectionExt, ActiveConnectionState, Client, ConnectionExt, DeviceExt, DeviceType,
+    SettingBond, SettingBridge, SettingConnection, SettingIPConfig, SettingVlan, SettingVlanExt,
+    SimpleConnection, SETTING_BOND_SETTING_NAME, SETTING_BRIDGE_SETTING_NAME,
+    SETTING_LOOPBACK_SETTING_NAME, SETTING_VLAN_SETTING_NAME, SETTING_WIRED_SETTING_NAME,
 };
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, VecDeque};
 
 /// The NMManger takes an argument, which is used to
@@ -13,6 +20,77 @@ pub struct MapNMManger {
     created_ifaces: VecDeque<String>,
 }
 
+/// The activation state of a connection, derived from the
+/// `NMActiveConnectionState` of the active connection bound to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkState {
+    Unknown,
+    Disconnected,
+    Connecting,
+    ConnectedLocal,
+    ConnectedSite,
+    ConnectedGlobal,
+    Deactivating,
+}
+
+impl NetworkState {
+    /// Map the `ActiveConnectionState` of the active connection currently
+    /// bound to `device` to a `NetworkState`. A device with no active
+    /// connection yet (nothing has started activating it) reports
+    /// `Disconnected`.
+    ///
+    /// `NMActiveConnectionState` has no Local/Site/Global distinction of
+    /// its own, so a fully `Activated` connection reports as the most
+    /// connected variant, `ConnectedGlobal`; `ConnectedLocal`/
+    /// `ConnectedSite` are not produced by this mapping.
+    fn from_device(device: &nm::Device) -> Self {
+        match device.active_connection().map(|active| active.state()) {
+            None | Some(ActiveConnectionState::Deactivated) => NetworkState::Disconnected,
+            Some(ActiveConnectionState::Unknown) => NetworkState::Unknown,
+            Some(ActiveConnectionState::Activating) => NetworkState::Connecting,
+            Some(ActiveConnectionState::Activated) => NetworkState::ConnectedGlobal,
+            Some(ActiveConnectionState::Deactivating) => NetworkState::Deactivating,
+            Some(_) => NetworkState::Unknown,
+        }
+    }
+}
+
+/// A point-in-time snapshot of one device known to NetworkManager, for
+/// diagnostics, health checks and status tooling.
+#[derive(Debug, Serialize)]
+pub struct DeviceSnapshot {
+    pub interface: String,
+    pub device_type: String,
+    pub connection_id: Option<String>,
+    pub carrier: bool,
+    pub ipv4_method: Option<String>,
+    pub ipv6_method: Option<String>,
+    pub ipv4: Option<IPConfig>,
+    pub ipv6: Option<IPConfig>,
+}
+
+/// The kind of interface a connection should be created as, together with
+/// the type-specific data needed to build its NetworkManager setting graph.
+///
+/// This is also the type a `NetworkProfile` document deserializes into
+/// (tagged on its `type` field), so it doubles as the declarative and the
+/// imperative representation of an interface kind - there is no separate
+/// profile-only enum to keep in sync with this one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum InterfaceType {
+    /// A plain wired Ethernet connection.
+    Ethernet,
+    /// A loopback connection.
+    Loopback,
+    /// A VLAN sub-interface of `parent`, tagged `id`.
+    Vlan { parent: String, id: u32 },
+    /// A bridge aggregating `slaves` as its member interfaces.
+    Bridge { slaves: Vec<String> },
+    /// A bond aggregating `slaves` as its member interfaces.
+    Bond { slaves: Vec<String> },
+}
+
 /// Get a Network Mananger connection by name. If there's no
 /// connection matches the name, it will create a new connection
 /// with DHCP.
@@ -67,24 +145,82 @@ impl MapNMManger {
         if self.map.contains_key(name) {}
     }
 
-    /// Create a new Ethernet Network Manager connection with the
-    /// connection name and device name.
-    fn create_connection(conn_name: &str, device_name: Option<&str>) -> nm::SimpleConnection {
+    /// Create a new Network Manager connection of the given interface type,
+    /// with the connection name and device name.
+    fn create_connection(
+        conn_name: &str,
+        device_name: Option<&str>,
+        iface_type: &InterfaceType,
+    ) -> nm::SimpleConnection {
         let connection = SimpleConnection::new();
         let s_connection = SettingConnection::new();
+        s_connection.set_id(Some(conn_name));
+        s_connection.set_autoconnect(true);
+        s_connection.set_interface_name(device_name);
 
+        match iface_type {
+            InterfaceType::Ethernet => {
+                s_connection.set_type(Some(&SETTING_WIRED_SETTING_NAME));
+            }
+            InterfaceType::Loopback => {
+                s_connection.set_type(Some(&SETTING_LOOPBACK_SETTING_NAME));
+            }
+            InterfaceType::Vlan { parent, id } => {
+                s_connection.set_type(Some(&SETTING_VLAN_SETTING_NAME));
+                let s_vlan = SettingVlan::new();
+                s_vlan.set_parent(Some(parent));
+                s_vlan.set_id(*id);
+                connection.add_setting(&s_vlan);
+            }
+            InterfaceType::Bridge { .. } => {
+                s_connection.set_type(Some(&SETTING_BRIDGE_SETTING_NAME));
+                connection.add_setting(&SettingBridge::new());
+            }
+            InterfaceType::Bond { .. } => {
+                s_connection.set_type(Some(&SETTING_BOND_SETTING_NAME));
+                connection.add_setting(&SettingBond::new());
+            }
+        }
+        connection.add_setting(&s_connection);
+        connection
+    }
+
+    /// Create a slave connection bound to `master`, of the given slave
+    /// type (a bridge or bond connection type name).
+    fn create_slave_connection(
+        conn_name: &str,
+        device_name: Option<&str>,
+        master: &str,
+        slave_type: &str,
+    ) -> nm::SimpleConnection {
+        let connection = SimpleConnection::new();
+        let s_connection = SettingConnection::new();
         s_connection.set_type(Some(&SETTING_WIRED_SETTING_NAME));
         s_connection.set_id(Some(conn_name));
         s_connection.set_autoconnect(true);
         s_connection.set_interface_name(device_name);
+        s_connection.set_master(Some(master));
+        s_connection.set_slave_type(Some(slave_type));
         connection.add_setting(&s_connection);
         connection
     }
 
     /// Get the connection by given connection name, if the connection
-    /// is not existed, it will be created according to the instance map
-    /// attribute.
+    /// is not existed, it will be created as a plain Ethernet connection
+    /// according to the instance map attribute.
     pub async fn connection_by_name(&mut self, name: &str) -> Result<nm::RemoteConnection> {
+        self.connection_by_type(name, InterfaceType::Ethernet).await
+    }
+
+    /// Get the connection by given connection name, if the connection is
+    /// not existed, it will be created as `iface_type` according to the
+    /// instance map attribute. For a bridge/bond, the member slave
+    /// connections are created and committed alongside the master.
+    pub async fn connection_by_type(
+        &mut self,
+        name: &str,
+        iface_type: InterfaceType,
+    ) -> Result<nm::RemoteConnection> {
         match self.map.contains_key(name) {
             true => {
                 let client = Client::new_future().await?;
@@ -95,10 +231,33 @@ impl MapNMManger {
                         let new_conn = MapNMManger::create_connection(
                             name,
                             self.map.get(name).map(|x| x.as_str()),
+                            &iface_type,
                         );
                         client.add_connection_future(&new_conn, true).await?;
                         // Add the new connection name to created_ifaces deque.
                         self.created_ifaces.push_back(name.to_string());
+
+                        if let InterfaceType::Bridge { slaves } | InterfaceType::Bond { slaves } =
+                            &iface_type
+                        {
+                            let slave_type = match &iface_type {
+                                InterfaceType::Bridge { .. } => {
+                                    SETTING_BRIDGE_SETTING_NAME.as_str()
+                                }
+                                InterfaceType::Bond { .. } => SETTING_BOND_SETTING_NAME.as_str(),
+                                _ => unreachable!(),
+                            };
+                            for slave in slaves {
+                                let slave_conn = MapNMManger::create_slave_connection(
+                                    &format!("{}-{}", name, slave),
+                                    Some(slave.as_str()),
+                                    name,
+                                    slave_type,
+                                );
+                                client.add_connection_future(&slave_conn, true).await?;
+                            }
+                        }
+
                         match client.connection_by_id(name) {
                             Some(connection) => conn = connection,
                             _ => bail!("Failed to get connection {}", name),
@@ -110,6 +269,96 @@ impl MapNMManger {
             _ => bail!("Failed to get connection {}", name),
         }
     }
+
+    /// Watch the activation state of the connection `name`, as a stream of
+    /// `NetworkState` updates.
+    ///
+    /// Resolves the device bound to `name` by interface name, without
+    /// creating or otherwise touching a connection - this is read-only, so
+    /// watching a not-yet-existing VLAN/bridge/bond connection never
+    /// provisions it as plain Ethernet the way `connection_by_name` would.
+    /// Pushes the device's current state (derived from its active
+    /// connection) immediately, then pushes a new state every time
+    /// NetworkManager reports a transition, so callers can `await` until a
+    /// connection created elsewhere actually comes up instead of committing
+    /// and hoping.
+    pub async fn watch_state(&mut self, name: &str) -> Result<impl Stream<Item = NetworkState>> {
+        let device_name = self
+            .map
+            .get(name)
+            .ok_or_else(|| eyre!("No device mapped to connection {}", name))?;
+        let client = Client::new_future().await?;
+        let device = client
+            .devices()
+            .into_iter()
+            .find(|device| {
+                device
+                    .iface()
+                    .map_or(false, |iface| iface.as_str() == device_name.as_str())
+            })
+            .ok_or_else(|| eyre!("No device named {}", device_name))?;
+
+        let (tx, rx) = mpsc::unbounded();
+        let _ = tx.unbounded_send(NetworkState::from_device(&device));
+        device.connect_state_changed(move |device, _new_state, _old_state, _reason| {
+            let _ = tx.unbounded_send(NetworkState::from_device(device));
+        });
+        Ok(rx)
+    }
+
+    /// Snapshot the current state of every device NetworkManager knows
+    /// about as serializable `DeviceSnapshot`s (serializing them is left to
+    /// the caller): interface name, device type, bound connection id, carrier
+    /// state, the active method per protocol, and the resolved IP
+    /// configuration for both manual and DHCP connections.
+    pub async fn dump_state() -> Result<Vec<DeviceSnapshot>> {
+        let client = Client::new_future().await?;
+        let mut snapshots = Vec::new();
+        for device in client.devices() {
+            let connection_id = device
+                .active_connection()
+                .and_then(|active| active.id())
+                .map(|id| id.to_string());
+            let connection = connection_id
+                .as_deref()
+                .and_then(|id| client.connection_by_id(id));
+
+            let (ipv4_method, ipv6_method, ipv4, ipv6) = match &connection {
+                Some(connection) => {
+                    let ipv4_setting = connection.setting_ip4_config();
+                    let ipv6_setting = connection.setting_ip6_config();
+                    let ipv4_method = ipv4_setting
+                        .as_ref()
+                        .and_then(|x| x.method())
+                        .map(|x| x.to_string());
+                    let ipv6_method = ipv6_setting
+                        .as_ref()
+                        .and_then(|x| x.method())
+                        .map(|x| x.to_string());
+                    let ipv4 = ipv4_setting
+                        .map(|x| IPConfig::from_settings_report(x.upcast::<SettingIPConfig>()))
+                        .unwrap_or(None);
+                    let ipv6 = ipv6_setting
+                        .map(|x| IPConfig::from_settings_report(x.upcast::<SettingIPConfig>()))
+                        .unwrap_or(None);
+                    (ipv4_method, ipv6_method, ipv4, ipv6)
+                }
+                None => (None, None, None, None),
+            };
+
+            snapshots.push(DeviceSnapshot {
+                interface: device.iface().map(|x| x.to_string()).unwrap_or_default(),
+                device_type: format!("{:?}", device.device_type()),
+                connection_id,
+                carrier: device.carrier(),
+                ipv4_method,
+                ipv6_method,
+                ipv4,
+                ipv6,
+            });
+        }
+        Ok(snapshots)
+    }
 }
 
 #[cfg(test)]
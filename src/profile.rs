@@ -0,0 +1,249 @@
+use super::networkcfg::{IPConfig, NetworkConfig};
+use super::nmmgr::{InterfaceType, MapNMManger};
+use eyre::Result;
+use jsonschema::{Draft, JSONSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+/// The embedded JSON schema a `NetworkProfile` document is validated
+/// against before it is ever applied to NetworkManager.
+const PROFILE_SCHEMA: &str = include_str!("profile_schema.json");
+
+/// One interface entry in a `NetworkProfile` document.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InterfaceEntry {
+    /// The NetworkManager connection name.
+    pub name: String,
+    /// The device/interface this connection should be matched to.
+    pub device: String,
+    /// The kind of interface to create, and its type-specific data. Reuses
+    /// `nmmgr::InterfaceType` directly so there's a single definition of
+    /// what an interface kind is, instead of a profile-only lookalike that
+    /// `apply` would have to translate.
+    #[serde(flatten)]
+    pub iface_type: InterfaceType,
+    /// The IPv4 configuration of the interface, if any.
+    pub ipv4: Option<IPConfig>,
+    /// The IPv6 configuration of the interface, if any.
+    pub ipv6: Option<IPConfig>,
+}
+
+/// A full declarative description of a machine's networking, as read from
+/// a single JSON/YAML/TOML document.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NetworkProfile {
+    pub interfaces: Vec<InterfaceEntry>,
+}
+
+/// A single schema-validation violation found in a profile document.
+#[derive(Debug)]
+pub struct Violation {
+    /// The JSON pointer to the offending value.
+    pub path: String,
+    /// The human-readable description of the violation.
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Raised when a profile document fails schema validation. Carries every
+/// violation found, so a bad file can be fixed in one pass instead of
+/// round-tripping error by error.
+#[derive(Debug)]
+pub struct ProfileError {
+    pub violations: Vec<Violation>,
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "network profile failed schema validation:")?;
+        for violation in &self.violations {
+            writeln!(f, "  - {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl NetworkProfile {
+    /// Load a profile document from `path`, validating it against the
+    /// embedded schema before parsing it into a `NetworkProfile`.
+    ///
+    /// The format (JSON, YAML or TOML) is picked from the file extension;
+    /// anything else is treated as JSON.
+    ///
+    /// Returns a `ProfileError` listing every violation if the document is
+    /// malformed, so a bad file never reaches NetworkManager.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let value = Self::parse_document(path, &contents)?;
+        Self::validate(&value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Parse `contents` into a generic JSON `Value` according to the
+    /// format implied by `path`'s extension, so YAML/TOML documents can be
+    /// validated against the same JSON schema as JSON ones.
+    fn parse_document(path: &Path, contents: &str) -> Result<Value> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => {
+                let value: serde_yaml::Value = serde_yaml::from_str(contents)?;
+                Ok(serde_json::to_value(value)?)
+            }
+            Some("toml") => {
+                let value: toml::Value = toml::from_str(contents)?;
+                Ok(serde_json::to_value(value)?)
+            }
+            _ => Ok(serde_json::from_str(contents)?),
+        }
+    }
+
+    /// Validate a parsed document against the embedded schema.
+    fn validate(value: &Value) -> Result<()> {
+        let schema: Value = serde_json::from_str(PROFILE_SCHEMA)?;
+        let compiled = JSONSchema::options()
+            .with_draft(Draft::Draft7)
+            .compile(&schema)
+            .expect("embedded profile schema is valid");
+        if let Err(errors) = compiled.validate(value) {
+            let violations = errors
+                .map(|error| Violation {
+                    path: error.instance_path.to_string(),
+                    message: error.to_string(),
+                })
+                .collect();
+            return Err(ProfileError { violations }.into());
+        }
+        Ok(())
+    }
+
+    /// Apply every interface entry to NetworkManager, routing each through
+    /// `MapNMManger` and committing it in turn.
+    pub async fn apply(&self) -> Result<()> {
+        for entry in &self.interfaces {
+            let mut map = std::collections::BTreeMap::new();
+            map.insert(entry.name.clone(), entry.device.clone());
+            let mut mapnm_manger = MapNMManger::new_future(Some(&map)).await?;
+            mapnm_manger
+                .connection_by_type(&entry.name, entry.iface_type.clone())
+                .await?;
+
+            let config =
+                NetworkConfig::new(&entry.name, entry.ipv4.clone(), entry.ipv6.clone(), None);
+            config.save().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOD_JSON: &str = r#"{
+        "interfaces": [
+            {
+                "name": "eth0",
+                "device": "eth0",
+                "type": "ethernet",
+                "ipv4": { "addresses": [["192.168.1.10", 24]] }
+            }
+        ]
+    }"#;
+
+    const GOOD_YAML: &str = "interfaces:\n  - name: eth0\n    device: eth0\n    type: ethernet\n    ipv4:\n      addresses:\n        - [\"192.168.1.10\", 24]\n";
+
+    const GOOD_TOML: &str = "[[interfaces]]\nname = \"eth0\"\ndevice = \"eth0\"\ntype = \"ethernet\"\n\n[interfaces.ipv4]\naddresses = [[\"192.168.1.10\", 24]]\n";
+
+    fn downcast_violations(err: eyre::Report) -> Vec<Violation> {
+        err.downcast::<ProfileError>()
+            .expect("error should be a ProfileError")
+            .violations
+    }
+
+    #[test]
+    fn parse_document_picks_format_from_extension() {
+        let json = NetworkProfile::parse_document(Path::new("p.json"), GOOD_JSON).unwrap();
+        let yaml = NetworkProfile::parse_document(Path::new("p.yaml"), GOOD_YAML).unwrap();
+        let toml = NetworkProfile::parse_document(Path::new("p.toml"), GOOD_TOML).unwrap();
+        assert_eq!(json, yaml);
+        assert_eq!(json, toml);
+    }
+
+    #[test]
+    fn validate_accepts_a_well_formed_document() {
+        let value = NetworkProfile::parse_document(Path::new("p.json"), GOOD_JSON).unwrap();
+        NetworkProfile::validate(&value).expect("well-formed document should validate");
+    }
+
+    #[test]
+    fn validate_rejects_unknown_fields() {
+        let value: Value = serde_json::from_str(
+            r#"{"interfaces": [{"name": "eth0", "device": "eth0", "type": "ethernet", "bogus": true}]}"#,
+        )
+        .unwrap();
+        let violations = downcast_violations(NetworkProfile::validate(&value).unwrap_err());
+        assert!(!violations.is_empty());
+    }
+
+    #[test]
+    fn validate_rejects_missing_required_keys() {
+        let value: Value = serde_json::from_str(r#"{"interfaces": [{"name": "eth0"}]}"#).unwrap();
+        let violations = downcast_violations(NetworkProfile::validate(&value).unwrap_err());
+        assert!(violations
+            .iter()
+            .any(|v| v.message.to_lowercase().contains("required")));
+    }
+
+    #[test]
+    fn validate_rejects_malformed_addresses() {
+        let value: Value = serde_json::from_str(
+            r#"{"interfaces": [{"name": "eth0", "device": "eth0", "type": "ethernet",
+                "ipv4": {"addresses": [["not-an-ip", 24]]}}]}"#,
+        )
+        .unwrap();
+        let violations = downcast_violations(NetworkProfile::validate(&value).unwrap_err());
+        assert!(!violations.is_empty());
+    }
+
+    #[test]
+    fn validate_rejects_a_vlan_entry_missing_its_parent_and_id() {
+        let value: Value = serde_json::from_str(
+            r#"{"interfaces": [{"name": "vlan10", "device": "eth0.10", "type": "vlan"}]}"#,
+        )
+        .unwrap();
+        let violations = downcast_violations(NetworkProfile::validate(&value).unwrap_err());
+        assert!(!violations.is_empty());
+    }
+
+    #[test]
+    fn load_round_trips_through_disk_for_every_format() {
+        for (ext, contents) in [
+            ("json", GOOD_JSON),
+            ("yaml", GOOD_YAML),
+            ("toml", GOOD_TOML),
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "nmrpc-profile-test-{}-{}.{}",
+                std::process::id(),
+                ext,
+                ext
+            ));
+            fs::write(&path, contents).unwrap();
+            let profile = NetworkProfile::load(&path);
+            fs::remove_file(&path).ok();
+            let profile = profile.unwrap_or_else(|e| panic!("{} should load: {}", ext, e));
+            assert_eq!(profile.interfaces.len(), 1);
+            assert_eq!(profile.interfaces[0].name, "eth0");
+        }
+    }
+}